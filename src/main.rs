@@ -1,13 +1,13 @@
 use std::borrow::Cow;
 use std::future::Future;
 use std::path::PathBuf;
-use std::process::Stdio;
 
 use anyhow::*;
 use boolinator::Boolinator;
 use clap::{App, Arg};
 use colored::*;
 use once_cell::sync::{Lazy, OnceCell};
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
 use serde::{Deserialize, Serialize};
 use tempdir::TempDir;
 use walkdir::WalkDir;
@@ -15,9 +15,30 @@ use walkdir::WalkDir;
 #[derive(Debug, Default, Serialize, Deserialize)]
 struct Settings {
     language: String,
+    /// File extension (without the dot) used when writing a snippet to a
+    /// real temp file, e.g. `"cpp"`, `"rs"`, `"py"`.
+    extension: String,
     compilers: Vec<String>,
     compiler_options: Vec<String>,
-    dogear: String,
+    /// Command template run to produce `{out}` from `{src}`, e.g.
+    /// `"{compiler} {opts} -xc++ {src} -o {out}"`. Omit for interpreted
+    /// languages that have no separate compile step.
+    compile: Option<String>,
+    /// Command template that executes the snippet, e.g. `"{out}"` for a
+    /// compiled binary or `"{compiler} {src}"` for an interpreter.
+    run: String,
+}
+
+/// How a [`TestCase`] is expected to behave.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+enum TestMode {
+    /// Compile and run the snippet; success means the program exits with status 0
+    /// and, when `expected_stdout` is set, its stdout matches it.
+    #[default]
+    Run,
+    /// The snippet is expected to *fail* to compile; its normalized stderr is
+    /// compared against a committed `<path>.<counter>.<compiler>.stderr` snapshot.
+    CompileFail,
 }
 
 #[derive(Clone, Debug)]
@@ -27,74 +48,154 @@ struct TestCase {
     start: usize,
     end: usize,
     code: String,
+    mode: TestMode,
+    /// `no_run`: compile only, don't execute the produced binary.
+    no_run: bool,
+    /// `ignore`: parsed but skipped entirely, like an ignored doctest.
+    ignore: bool,
+    /// Captured from an immediately-following fenced ` ```text ` block, or
+    /// from a trailing `// expect: <text>` line in the snippet itself;
+    /// compared against the program's stdout in `run_tests`.
+    expected_stdout: Option<String>,
+}
+
+/// Parses a fence info string (e.g. `cpp run compile_fail`) into a language
+/// token plus the space-separated directive attributes that follow it.
+fn parse_info_string(info: &str) -> (&str, Vec<&str>) {
+    let mut parts = info.split_whitespace();
+    let lang = parts.next().unwrap_or("");
+    (lang, parts.collect())
 }
 
-fn read_the_docs(path: impl Into<PathBuf>, language: &str, dogear: &str) -> Result<Vec<TestCase>> {
-    use std::fs::File;
-    use std::io::{BufRead, BufReader};
+/// Strips a trailing `// expect: <text>` trailer line from a snippet, if
+/// present, returning the snippet without that line and the expected
+/// stdout it names. This is the inline alternative to an adjacent fenced
+/// ` ```text ` block for asserting a snippet's stdout.
+fn extract_expect_trailer(code: &str) -> (String, Option<String>) {
+    match code.lines().last().and_then(|line| {
+        line.trim_start()
+            .strip_prefix("// expect:")
+            .map(|rest| rest.trim().to_string())
+    }) {
+        Some(expected) => {
+            let without_trailer = code.rsplit_once('\n').map_or("", |(head, _)| head);
+            (without_trailer.to_string(), Some(expected))
+        }
+        None => (code.to_string(), None),
+    }
+}
 
+fn read_the_docs(path: impl Into<PathBuf>, language: &str) -> Result<Vec<TestCase>> {
     let path = &path.into();
-    let mut header: [String; 4] = ["".into(), "".into(), "".into(), "".into()];
-    let mut codes = Vec::new();
-    let mut buffer = Vec::new();
-    let mut line_start = 0usize;
-    let mut in_code_block = false;
-    let mut in_test_code_block = false;
-    let mut is_specified_lang = false;
-    let lang_code = format!(r#"```{}"#, language);
-
-    for (num, line) in BufReader::new(File::open(path)?).lines().enumerate() {
-        let line = line?;
-        if line.starts_with(r#"```"#) {
-            if in_code_block {
-                if in_test_code_block {
-                    codes.push(TestCase {
-                        path: path.to_string_lossy().to_string(),
-                        header: format!(
-                            "{:?}",
-                            header
-                                .iter()
-                                .filter(|h| !h.is_empty())
-                                .map(|h| h.trim_matches(' '))
-                                .collect::<Vec<_>>()
-                        ),
-                        start: line_start,
-                        end: num,
-                        code: buffer.join("\n"),
-                    });
-                    buffer.clear();
-                }
-                is_specified_lang = false;
-                in_test_code_block = false;
-            } else {
-                is_specified_lang = line.starts_with(&lang_code);
-                line_start = num;
+    let source = std::fs::read_to_string(path)?;
+    let line_of = |offset: usize| source[..offset].matches('\n').count();
+
+    // one slot per ATX heading level; a heading overwrites only its own
+    // level's slot, so deeper slots keep the last heading seen at that depth.
+    let mut header: [String; 6] = Default::default();
+    let mut heading: Option<(usize, String)> = None;
+    let mut block: Option<(String, Vec<String>, usize, String)> = None;
+    let mut codes: Vec<TestCase> = Vec::new();
+    // index of the just-pushed TestCase, kept alive only while the very next
+    // event is the start of a ` ```text ` fence, so it can capture the
+    // expected stdout for the block that precedes it.
+    let mut pending_case_index: Option<usize> = None;
+    let mut capturing_expect: Option<usize> = None;
+
+    for (event, range) in Parser::new_ext(&source, Options::empty()).into_offset_iter() {
+        if !matches!(event, Event::Start(Tag::CodeBlock(_))) {
+            pending_case_index = None;
+        }
+        match event {
+            Event::Start(Tag::Heading(level, ..)) => {
+                heading = Some((level as usize - 1, String::new()));
             }
-            in_code_block = !in_code_block;
-        } else {
-            // read a header if starts with `#`
-            if !in_code_block && line.starts_with('#') {
-                let len = line.len();
-                let title = line.trim_start_matches('#').to_string();
-                header[len - title.len() - 1] = title.to_string();
+            Event::End(Tag::Heading(..)) => {
+                if let Some((level, text)) = heading.take() {
+                    header[level] = text;
+                }
             }
-            if in_code_block {
-                if !in_test_code_block {
-                    in_test_code_block = is_specified_lang && line == dogear;
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                let (lang, attrs) = parse_info_string(&info);
+                if lang == "text" && pending_case_index.is_some() {
+                    capturing_expect = pending_case_index.take();
+                    block = Some(("text".to_string(), vec![], line_of(range.start), String::new()));
+                } else if lang == language {
+                    capturing_expect = None;
+                    block = Some((
+                        lang.to_string(),
+                        attrs.into_iter().map(str::to_string).collect(),
+                        line_of(range.start),
+                        String::new(),
+                    ));
                 } else {
-                    buffer.push(line.to_string());
+                    capturing_expect = None;
+                    block = None;
                 }
             }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, _, _, code)) = &mut block {
+                    code.push_str(&text);
+                } else if let Some((_, heading_text)) = &mut heading {
+                    heading_text.push_str(&text);
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some(idx) = capturing_expect.take() {
+                    if let Some((_, _, _, text)) = block.take() {
+                        codes[idx].expected_stdout = Some(text.trim_end_matches('\n').to_string());
+                    }
+                } else if let Some((lang, attrs, start, code)) = block.take() {
+                    if lang == language {
+                        let mode = attrs
+                            .iter()
+                            .any(|a| a == "compile_fail")
+                            .as_some(TestMode::CompileFail)
+                            .unwrap_or_default();
+                        let (code, expected_stdout) =
+                            extract_expect_trailer(code.trim_end_matches('\n'));
+                        codes.push(TestCase {
+                            path: path.to_string_lossy().to_string(),
+                            header: format!(
+                                "{:?}",
+                                header
+                                    .iter()
+                                    .filter(|h| !h.is_empty())
+                                    .collect::<Vec<_>>()
+                            ),
+                            start,
+                            end: line_of(range.end),
+                            code,
+                            mode,
+                            no_run: attrs.iter().any(|a| a == "no_run"),
+                            ignore: attrs.iter().any(|a| a == "ignore"),
+                            expected_stdout,
+                        });
+                        pending_case_index = Some(codes.len() - 1);
+                    }
+                }
+            }
+            _ => {}
         }
     }
     Ok(codes)
 }
 
+/// The verdict for a single `(TestCase, compiler)` job.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+enum Outcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct Report<'a> {
     filename: Cow<'a, str>,
     line: [usize; 2],
     compiler: Cow<'a, str>,
+    outcome: Outcome,
+    elapsed_ms: u128,
     info: Cow<'a, str>,
 }
 
@@ -104,15 +205,24 @@ impl<'a> Report<'a> {
             filename: case.path.clone().into(),
             line: [case.start, case.end],
             compiler: compiler.into(),
+            outcome: Outcome::Passed,
+            elapsed_ms: 0,
             info: "".into(),
         }
     }
     fn with_info(self, info: impl Into<Cow<'a, str>>) -> Report<'a> {
         Report {
-            filename: self.filename.to_owned(),
-            line: self.line.to_owned(),
-            compiler: self.compiler.to_owned(),
             info: info.into(),
+            ..self
+        }
+    }
+    fn with_outcome(self, outcome: Outcome) -> Report<'a> {
+        Report { outcome, ..self }
+    }
+    fn with_elapsed(self, elapsed: std::time::Duration) -> Report<'a> {
+        Report {
+            elapsed_ms: elapsed.as_millis(),
+            ..self
         }
     }
 }
@@ -120,73 +230,251 @@ impl<'a> Report<'a> {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct Reports<'a>(Vec<Report<'a>>);
 
-type TestResult<'a> = Result<String, Report<'a>>;
+/// Normalizes compiler stderr so a snapshot compares equal across machines:
+/// the workspace `TempDir` path is replaced by a placeholder, `\` is
+/// collapsed to `/`, trailing whitespace is stripped per line, and any
+/// `:<line>:<col>` span is blanked out.
+fn normalize_stderr(raw: &str, workspace: &std::path::Path) -> String {
+    let workspace = workspace.to_string_lossy().replace('\\', "/");
+    raw.replace('\\', "/")
+        .lines()
+        .map(|line| blank_span(line.replace(&workspace, "$WORKSPACE").trim_end()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Blanks `:<digits>:<digits>` occurrences (e.g. `foo.cpp:12:5`) to `:LINE:COL`
+/// so line/column drift between compiler versions doesn't break a snapshot.
+fn blank_span(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut out = String::with_capacity(line.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == ':' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+            let mut j = i + 1;
+            while j < chars.len() && chars[j].is_ascii_digit() {
+                j += 1;
+            }
+            if chars.get(j) == Some(&':') && chars.get(j + 1).is_some_and(|c| c.is_ascii_digit()) {
+                let mut k = j + 1;
+                while k < chars.len() && chars[k].is_ascii_digit() {
+                    k += 1;
+                }
+                out.push_str(":LINE:COL");
+                i = k;
+                continue;
+            }
+        }
+        out.push(chars[i]);
+        i += 1;
+    }
+    out
+}
+
+/// Compares `actual` against the expected-output file at `path`. The
+/// expected file is written (\"blessed\") when it is absent or when
+/// `--bless` was passed; otherwise a non-empty diff fails the test.
+fn compare_or_bless(path: &std::path::Path, actual: &str) -> std::result::Result<(), String> {
+    let bless = *BLESS.get().unwrap_or(&false);
+    if bless || !path.exists() {
+        std::fs::write(path, actual)
+            .unwrap_or_else(|e| panic!("failed to write expected-output file {:?}: {}", path, e));
+        return Ok(());
+    }
+    let expected = std::fs::read_to_string(path).unwrap_or_default();
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(format!(
+            "stderr did not match {:?}\n--- expected\n{}\n--- actual\n{}",
+            path, expected, actual
+        ))
+    }
+}
+
+/// Splits a command template into argv, substituting `{compiler}`, `{src}`
+/// and `{out}` in every token and expanding a bare `{opts}` token into the
+/// configured `compiler_options`.
+fn render_command(template: &str, compiler: &str, src: &std::path::Path, out: &std::path::Path) -> Vec<String> {
+    template
+        .split_whitespace()
+        .flat_map(|token| {
+            if token == "{opts}" {
+                Settings::global().compiler_options.clone()
+            } else {
+                vec![token
+                    .replace("{compiler}", compiler)
+                    .replace("{src}", &src.to_string_lossy())
+                    .replace("{out}", &out.to_string_lossy())]
+            }
+        })
+        .collect()
+}
+
+/// A minimal xorshift64 PRNG, just enough to seed a reproducible shuffle
+/// order without pulling in a full `rand` dependency.
+struct Xorshift64 {
+    state: u64,
+}
+
+impl Xorshift64 {
+    fn new(seed: u64) -> Xorshift64 {
+        Xorshift64 {
+            state: if seed == 0 { 0xdead_beef_dead_beef } else { seed },
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Fisher-Yates shuffle, in place.
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+/// Picks a seed for `--shuffle` when none was given via `--seed`, so the
+/// chosen order can still be reported and replayed.
+fn random_seed() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or(0x1234_5678_9abc_def0)
+}
+
+/// FNV-1a, used to mix a snippet's path into the `--shuffle` seed so
+/// different files don't all shuffle identically under the same seed.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
 
 async fn run_tests<'a>(
     test_case: &'a TestCase,
     settings: &'static Settings,
     workspace: &'static TempDir,
     counter: usize,
-) -> Vec<impl Future<Output = anyhow::Result<TestResult<'a>>> + 'a> {
+) -> Vec<impl Future<Output = anyhow::Result<Report<'a>>> + 'a> {
     use std::time::Instant;
     use tokio::process::Command;
 
-    settings
-        .compilers
-        .iter()
+    let mut compilers: Vec<&String> = settings.compilers.iter().collect();
+    if let Some(seed) = SEED.get().copied().flatten() {
+        Xorshift64::new(seed ^ counter as u64 ^ fnv1a(test_case.path.as_bytes())).shuffle(&mut compilers);
+    }
+
+    compilers
+        .into_iter()
         .map(move |compiler| async move {
             let start = Instant::now();
-            let exe = format!(
-                "{}-{}.out",
-                counter,
-                std::path::Path::new(compiler)
-                    .file_stem()
-                    .unwrap()
-                    .to_string_lossy()
-            );
-            let exe = workspace.path().join(exe);
-            // piped echo
-            let echo = std::process::Command::new("echo")
-                .arg(&test_case.code)
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("piped echo failed.");
-            // compiles a test
-            let compile_output = Command::new(compiler)
-                .args(settings.compiler_options.clone())
-                .args(&["-o", &exe.to_string_lossy()])
-                .arg("-xc++")
-                .arg("-")
-                .stdin(echo.stdout.unwrap())
-                .output()
-                .await // compile
-                .with_context(|| anyhow!("failed to execute compile process"))?;
-            if compile_output.status.success() {
-                let test_output = Command::new(&exe)
+            let report = Report::from(test_case, compiler);
+            if test_case.ignore {
+                return Ok(report.with_outcome(Outcome::Ignored));
+            }
+
+            let stem = std::path::Path::new(compiler)
+                .file_stem()
+                .unwrap()
+                .to_string_lossy();
+            let src = workspace
+                .path()
+                .join(format!("{}-{}.{}", counter, stem, settings.extension));
+            let out = workspace.path().join(format!("{}-{}.out", counter, stem));
+            std::fs::write(&src, &test_case.code)
+                .with_context(|| anyhow!("failed to write snippet to {}", src.to_string_lossy()))?;
+
+            async fn run_command(argv: &[String]) -> anyhow::Result<std::process::Output> {
+                let (program, args) = argv.split_first().expect("command template is empty");
+                Command::new(program)
+                    .args(args)
                     .output()
                     .await
-                    .with_context(|| anyhow!("failed to execute test {}", exe.to_string_lossy()))?;
-                Ok(test_output.status.success().as_result_from(
-                    move || {
-                        format! {
-                            "Passed: {file} ({header} [line: {begin}-{end}], time: {elapsed} ms)",
-                            file = test_case.path,
-                            header = test_case.header,
-                            begin = test_case.start,
-                            end = test_case.end,
-                            elapsed = start.elapsed().subsec_millis(),
-                        }
-                    },
-                    move || {
-                        Report::from(test_case, compiler)
-                            .with_info(String::from_utf8(test_output.stderr).unwrap())
-                    },
-                ))
-            } else {
-                Ok(Err(Report::from(test_case, compiler).with_info(
-                    String::from_utf8(compile_output.stderr).unwrap(),
-                )))
+                    .with_context(|| anyhow!("failed to execute {}", program))
+            }
+
+            let compile_output = match &settings.compile {
+                Some(template) => {
+                    Some(run_command(&render_command(template, compiler, &src, &out)).await?)
+                }
+                None => None,
+            };
+
+            if test_case.mode == TestMode::CompileFail {
+                // When there is no separate compile step, running the
+                // snippet stands in for "compiling" it: a non-zero exit
+                // means the interpreter rejected it.
+                let failure_output = match compile_output {
+                    Some(output) => output,
+                    None => run_command(&render_command(&settings.run, compiler, &src, &out)).await?,
+                };
+                return Ok(if failure_output.status.success() {
+                    report.with_outcome(Outcome::Failed).with_info(
+                        "expected a compile error, but compilation succeeded".to_string(),
+                    )
+                } else {
+                    let actual = normalize_stderr(
+                        &String::from_utf8(failure_output.stderr).unwrap(),
+                        workspace.path(),
+                    );
+                    let snapshot = std::path::PathBuf::from(format!(
+                        "{}.{}.{}.stderr",
+                        test_case.path, counter, stem
+                    ));
+                    match compare_or_bless(&snapshot, &actual) {
+                        Ok(()) => report.with_outcome(Outcome::Passed),
+                        Err(diff) => report.with_outcome(Outcome::Failed).with_info(diff),
+                    }
+                }
+                .with_elapsed(start.elapsed()));
             }
+
+            if let Some(compile_output) = &compile_output {
+                if !compile_output.status.success() {
+                    return Ok(report
+                        .with_outcome(Outcome::Failed)
+                        .with_info(String::from_utf8(compile_output.stderr.clone()).unwrap())
+                        .with_elapsed(start.elapsed()));
+                }
+            }
+            if test_case.no_run {
+                return Ok(report
+                    .with_outcome(Outcome::Passed)
+                    .with_elapsed(start.elapsed()));
+            }
+
+            let test_output = run_command(&render_command(&settings.run, compiler, &src, &out)).await?;
+            let stdout = String::from_utf8_lossy(&test_output.stdout).into_owned();
+            let stdout_matches = test_case
+                .expected_stdout
+                .as_ref()
+                .is_none_or(|expected| stdout.trim_end() == expected.trim_end());
+            let report = if test_output.status.success() && stdout_matches {
+                report.with_outcome(Outcome::Passed)
+            } else {
+                let info = if !stdout_matches {
+                    format!(
+                        "stdout did not match\n--- expected\n{}\n--- actual\n{}",
+                        test_case.expected_stdout.as_deref().unwrap_or(""),
+                        stdout,
+                    )
+                } else {
+                    String::from_utf8(test_output.stderr).unwrap()
+                };
+                report.with_outcome(Outcome::Failed).with_info(info)
+            };
+            Ok(report.with_elapsed(start.elapsed()))
         })
         .collect::<Vec<_>>()
 }
@@ -218,11 +506,67 @@ fn create_my_app() -> clap::App<'static, 'static> {
                 .takes_value(true)
                 .required(true),
         )
+        .arg(
+            Arg::with_name("bless")
+                .long("bless")
+                .help("Overwrite compile_fail expected-output snapshots instead of comparing against them"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .short("w")
+                .long("watch")
+                .help("After the initial pass, keep running and re-test changed Markdown files"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Emit a machine-readable test report in this format")
+                .takes_value(true)
+                .possible_values(&["junit", "tap"]),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("FILE")
+                .help("Write the --format report to this file instead of stdout")
+                .takes_value(true)
+                .requires("format"),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .value_name("SUBSTRING")
+                .help("Only run snippets whose file path or header contains SUBSTRING")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shuffle")
+                .long("shuffle")
+                .help("Dispatch snippets and their compiler jobs in a randomized order"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed for --shuffle; a random seed is chosen and printed when omitted")
+                .takes_value(true)
+                .requires("shuffle"),
+        )
 }
 
 static INSTANCE: OnceCell<Settings> = OnceCell::new();
 static WORKSPACE: Lazy<TempDir> =
     Lazy::new(|| TempDir::new("workspace").expect("failed to create workspace directory"));
+/// Whether `compile_fail` expected-output snapshots should be (re)written
+/// instead of compared against. Set once from the `--bless` flag in `main`.
+static BLESS: OnceCell<bool> = OnceCell::new();
+/// Substring a snippet's file path or header stack must contain to run.
+/// Set once from the `--filter` flag in `main`.
+static FILTER: OnceCell<Option<String>> = OnceCell::new();
+/// Seed for randomizing snippet and compiler-job dispatch order, set once
+/// from `--shuffle`/`--seed` in `main`. `None` means document order.
+static SEED: OnceCell<Option<u64>> = OnceCell::new();
 
 impl Settings {
     pub fn global() -> &'static Settings {
@@ -236,6 +580,245 @@ impl Settings {
     }
 }
 
+/// Formats a single job's [`Report`] as a colored, human-readable status line.
+fn describe(case: &TestCase, report: &Report) -> String {
+    let line = format! {
+        "{file} ({header} [line: {begin}-{end}], compiler: {compiler}, time: {elapsed} ms)",
+        file = report.filename,
+        header = case.header,
+        begin = report.line[0],
+        end = report.line[1],
+        compiler = report.compiler,
+        elapsed = report.elapsed_ms,
+    };
+    match report.outcome {
+        Outcome::Passed => format!("Passed: {}", line).green().to_string(),
+        Outcome::Ignored => format!("Ignored: {}", line).yellow().to_string(),
+        Outcome::Failed => format!("Failed: {}", line).red().to_string(),
+    }
+}
+
+/// Reads and runs every test case in a single Markdown file, printing each
+/// job's result as it completes. Non-`.md` paths are silently skipped so
+/// this can be called on any `WalkDir`/watcher-observed path. The returned
+/// reports (serialized as JSON, to escape `tokio::spawn`'s `'static`
+/// requirement) cover every outcome, not just failures.
+async fn test_file(path: &std::path::Path) -> Result<Vec<String>> {
+    if !path.to_string_lossy().ends_with(".md") {
+        return Ok(Vec::new());
+    }
+    let cases = read_the_docs(path, &Settings::global().language)
+        .with_context(|| anyhow!("ERROR: fail to read the docs"))?;
+
+    // `counter` is assigned before filtering/shuffling so it always reflects
+    // a snippet's position in document order, keeping `compile_fail`
+    // snapshot filenames (`{path}.{counter}.{compiler}.stderr`) stable regardless of
+    // `--filter`/`--shuffle`.
+    let mut cases: Vec<(usize, TestCase)> = cases.into_iter().enumerate().collect();
+    if let Some(filter) = FILTER.get().and_then(Option::as_ref) {
+        cases.retain(|(_, case)| case.path.contains(filter) || case.header.contains(filter));
+    }
+    if let Some(seed) = SEED.get().copied().flatten() {
+        Xorshift64::new(seed ^ fnv1a(path.to_string_lossy().as_bytes())).shuffle(&mut cases);
+    }
+
+    let mut reports = Vec::new();
+    let job_queue = cases
+        .into_iter()
+        .map(|(counter, code)| {
+            tokio::spawn(async move {
+                let mut reports = Vec::new();
+                for job in run_tests(&code, Settings::global(), &WORKSPACE, counter).await {
+                    let report = job.await?;
+                    println!("{}", describe(&code, &report));
+                    reports.push(serde_json::to_string(&report).unwrap());
+                }
+                anyhow::Result::<Option<Vec<String>>>::Ok((!reports.is_empty()).as_some(reports))
+            })
+        })
+        .collect::<Vec<_>>();
+    for job in job_queue {
+        if let Some(results) = job.await?? {
+            reports.extend(results);
+        }
+    }
+    Ok(reports)
+}
+
+/// Walks `directory` and runs [`test_file`] on every entry.
+async fn test_directory(directory: &str) -> Result<Vec<String>> {
+    let mut reports = Vec::new();
+    for entry in WalkDir::new(directory)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(Result::ok)
+    {
+        reports.extend(test_file(&entry.into_path()).await?);
+    }
+    Ok(reports)
+}
+
+/// Deserializes a batch of JSON-encoded [`Report`]s collected by [`test_file`].
+fn parse_reports(reports: &[String]) -> Vec<Report<'static>> {
+    reports
+        .iter()
+        .map(|report| serde_json::from_str(report).unwrap())
+        .collect()
+}
+
+/// Prints a pass/fail summary for a batch of serialized [`Report`]s and
+/// returns whether every one of them passed. An `Ignored` report never
+/// counts as a failure.
+fn print_summary(reports: &[String]) -> bool {
+    let failed = parse_reports(reports)
+        .into_iter()
+        .filter(|report| report.outcome == Outcome::Failed)
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        println!("{}", "All Tests Passed".green());
+        return true;
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&Reports(failed)).unwrap().red()
+    );
+    false
+}
+
+/// Escapes `&`, `<`, `>`, and `"` for inclusion in XML text/attribute content.
+fn xml_escape(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders a batch of [`Report`]s as a JUnit XML `<testsuite>`, one
+/// `<testcase>` per `(TestCase, compiler)` job, so CI systems can surface
+/// per-snippet failures with file name, line range, compiler, and elapsed time.
+/// `seed`, if the run was dispatched via `--shuffle`, is recorded as a
+/// `<properties>` entry so a flaky order can be reproduced from the report alone.
+fn render_junit(reports: &[Report], seed: Option<u64>) -> String {
+    let failures = reports.iter().filter(|r| r.outcome == Outcome::Failed).count();
+    let skipped = reports.iter().filter(|r| r.outcome == Outcome::Ignored).count();
+    let mut xml = format! {
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuite name=\"mkdocs-smoke-test\" tests=\"{tests}\" failures=\"{failures}\" skipped=\"{skipped}\">\n",
+        tests = reports.len(),
+        failures = failures,
+        skipped = skipped,
+    };
+    if let Some(seed) = seed {
+        xml.push_str(&format!(
+            "  <properties>\n    <property name=\"shuffle.seed\" value=\"{}\"/>\n  </properties>\n",
+            seed
+        ));
+    }
+    for report in reports {
+        xml.push_str(&format! {
+            "  <testcase classname=\"{compiler}\" name=\"{name}\" time=\"{time}\">\n",
+            compiler = xml_escape(&report.compiler),
+            name = xml_escape(&format!(
+                "{} [line: {}-{}]",
+                report.filename, report.line[0], report.line[1]
+            )),
+            time = report.elapsed_ms as f64 / 1000.0,
+        });
+        match report.outcome {
+            Outcome::Failed => xml.push_str(&format!(
+                "    <failure message=\"smoke test failed\">{}</failure>\n",
+                xml_escape(&report.info)
+            )),
+            Outcome::Ignored => xml.push_str("    <skipped/>\n"),
+            Outcome::Passed => {}
+        }
+        xml.push_str("  </testcase>\n");
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+/// Renders a batch of [`Report`]s as a TAP (Test Anything Protocol) stream.
+/// `seed`, if the run was dispatched via `--shuffle`, is recorded as a
+/// leading comment so a flaky order can be reproduced from the report alone.
+fn render_tap(reports: &[Report], seed: Option<u64>) -> String {
+    let mut tap = format!("TAP version 13\n1..{}\n", reports.len());
+    if let Some(seed) = seed {
+        tap.push_str(&format!("# shuffle seed: {}\n", seed));
+    }
+    for (i, report) in reports.iter().enumerate() {
+        let number = i + 1;
+        let name = format!(
+            "{} [line: {}-{}] ({}, {} ms)",
+            report.filename, report.line[0], report.line[1], report.compiler, report.elapsed_ms
+        );
+        match report.outcome {
+            Outcome::Passed => tap.push_str(&format!("ok {} - {}\n", number, name)),
+            Outcome::Ignored => tap.push_str(&format!("ok {} - {} # SKIP\n", number, name)),
+            Outcome::Failed => {
+                tap.push_str(&format!("not ok {} - {}\n", number, name));
+                for line in report.info.lines() {
+                    tap.push_str(&format!("  # {}\n", line));
+                }
+            }
+        }
+    }
+    tap
+}
+
+/// Watches `directory` for changes to `.md` files and re-tests just the
+/// affected file on each debounced event, reusing the existing `WORKSPACE`.
+/// `directory` is canonicalized up front (against the process's initial
+/// working directory) so the watch survives later relative-path edits.
+async fn watch(directory: &std::path::Path) -> Result<()> {
+    use notify::{DebouncedEvent, RecursiveMode, Watcher};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    let directory = directory.canonicalize()?;
+    let (tx, rx) = channel();
+    let mut watcher = notify::watcher(tx, Duration::from_millis(500))
+        .with_context(|| anyhow!("failed to start file watcher"))?;
+    watcher
+        .watch(&directory, RecursiveMode::Recursive)
+        .with_context(|| anyhow!("failed to watch {}", directory.to_string_lossy()))?;
+    println!(
+        "{}",
+        format!(
+            "Watching {} for changes... (Ctrl-C to stop)",
+            directory.to_string_lossy()
+        )
+        .cyan()
+    );
+
+    // notify's watcher delivers events onto an unbounded std channel whose
+    // receiver blocks, so a dedicated OS thread drains it and relays paths
+    // of interest onto a tokio channel the async loop below can await.
+    let (changed_tx, mut changed_rx) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let _watcher = watcher;
+        for event in rx {
+            let path = match event {
+                DebouncedEvent::Write(path) | DebouncedEvent::Create(path) => path,
+                _ => continue,
+            };
+            if path.extension().is_some_and(|ext| ext == "md") && changed_tx.send(path).is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(path) = changed_rx.recv().await {
+        println!("{}", format!("Re-testing {}", path.to_string_lossy()).cyan());
+        match test_file(&path).await {
+            Ok(reports) => {
+                print_summary(&reports);
+            }
+            Err(err) => println!("{}", format!("ERROR: {:#}", err).red()),
+        }
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Create App
@@ -249,67 +832,219 @@ async fn main() -> Result<()> {
         let settings = Settings::init_from(config)?;
         INSTANCE.set(settings).unwrap();
     }
-    let mut reports = Vec::new();
+    BLESS.set(matches.is_present("bless")).unwrap();
+    FILTER
+        .set(matches.value_of("filter").map(str::to_string))
+        .unwrap();
+    let seed = matches.is_present("shuffle").as_some_from(|| {
+        matches
+            .value_of("seed")
+            .map(|seed| seed.parse().expect("--seed must be a non-negative integer"))
+            .unwrap_or_else(random_seed)
+    });
+    if let Some(seed) = seed {
+        println!("{}", format!("Shuffling with seed {}", seed).cyan());
+    }
+    SEED.set(seed).unwrap();
 
-    for entry in WalkDir::new(directory)
-        .follow_links(true)
-        .into_iter()
-        .filter_map(Result::ok)
-    {
-        let path = entry.into_path();
-        if path.to_string_lossy().ends_with(".md") {
-            let cases = read_the_docs(
-                &path,
-                &Settings::global().language,
-                &Settings::global().dogear,
-            )
-            .with_context(|| anyhow!("ERROR: fail to read the docs"))?;
-
-            let job_queue = cases
-                .into_iter()
-                .enumerate()
-                .map(|(counter, code)| {
-                    tokio::spawn(async move {
-                        let mut reports = Vec::new();
-                        for job in run_tests(&code, Settings::global(), &WORKSPACE, counter).await {
-                            match job.await? {
-                                Err(report) => {
-                                    let err = serde_json::to_string(&report).unwrap();
-                                    println!("{}", err.red());
-                                    reports.push(err);
-                                }
-                                Ok(res) => {
-                                    println!("{}", res);
-                                }
-                            }
-                        }
-                        anyhow::Result::<Option<Vec<String>>>::Ok(
-                            (!reports.is_empty()).as_some(reports.clone()),
-                        )
-                    })
-                })
-                .collect::<Vec<_>>();
-            for job in job_queue {
-                if let Some(errors) = job.await?? {
-                    reports.extend(errors);
-                }
-            }
+    let reports = test_directory(directory).await?;
+    let passed = print_summary(&reports);
+
+    if let Some(format) = matches.value_of("format") {
+        let parsed = parse_reports(&reports);
+        let seed = SEED.get().copied().flatten();
+        let rendered = match format {
+            "junit" => render_junit(&parsed, seed),
+            "tap" => render_tap(&parsed, seed),
+            _ => unreachable!("clap restricts --format to known values"),
+        };
+        match matches.value_of("output") {
+            Some(path) => std::fs::write(path, rendered)
+                .with_context(|| anyhow!("failed to write report to {}", path))?,
+            None => print!("{}", rendered),
         }
     }
-    if !reports.is_empty() {
-        let reports = reports
-            .into_iter()
-            .map(|report| serde_json::from_str(&report).unwrap())
-            .collect::<Vec<_>>();
-        anyhow::bail!(
-            "{}",
-            serde_json::to_string_pretty(&Reports(reports))
-                .unwrap()
-                .red()
-        );
+
+    if matches.is_present("watch") {
+        watch(std::path::Path::new(directory)).await?;
+        if passed {
+            std::fs::remove_dir_all(&*WORKSPACE)?;
+        }
+        return Ok(());
     }
 
-    println!("{}", "All Tests Passed".green());
+    if !passed {
+        anyhow::bail!("mkdocs-smoke-test found failing snippets");
+    }
     std::fs::remove_dir_all(&*WORKSPACE)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blank_span_blanks_line_col_spans() {
+        assert_eq!(blank_span("foo.cpp:12:5: error: nope"), "foo.cpp:LINE:COL: error: nope");
+        assert_eq!(blank_span("no span here"), "no span here");
+        assert_eq!(blank_span("foo.cpp:12 no second colon"), "foo.cpp:12 no second colon");
+    }
+
+    #[test]
+    fn normalize_stderr_replaces_workspace_and_trims() {
+        let workspace = std::path::Path::new("/tmp/workspace");
+        let raw = "/tmp/workspace/0-g++.cpp:3:1: error: trailing   \r\n";
+        assert_eq!(
+            normalize_stderr(raw, workspace),
+            "$WORKSPACE/0-g++.cpp:LINE:COL: error: trailing"
+        );
+    }
+
+    #[test]
+    fn parse_info_string_splits_language_and_attrs() {
+        assert_eq!(parse_info_string("cpp run should_panic"), ("cpp", vec!["run", "should_panic"]));
+        assert_eq!(parse_info_string("cpp"), ("cpp", vec![]));
+        assert_eq!(parse_info_string(""), ("", vec![]));
+    }
+
+    #[test]
+    fn render_command_substitutes_tokens_and_expands_opts() {
+        INSTANCE.get_or_init(|| Settings {
+            language: "cpp".to_string(),
+            extension: "cpp".to_string(),
+            compilers: vec!["g++".to_string()],
+            compiler_options: vec!["-Wall".to_string(), "-std=c++17".to_string()],
+            compile: None,
+            run: String::new(),
+        });
+        let argv = render_command(
+            "{compiler} {opts} {src} -o {out}",
+            "g++",
+            std::path::Path::new("/tmp/0-g++.cpp"),
+            std::path::Path::new("/tmp/0-g++.out"),
+        );
+        assert_eq!(
+            argv,
+            vec!["g++", "-Wall", "-std=c++17", "/tmp/0-g++.cpp", "-o", "/tmp/0-g++.out"]
+        );
+    }
+
+    fn sample_reports() -> Vec<Report<'static>> {
+        vec![
+            Report {
+                filename: "docs/index.md".into(),
+                line: [1, 5],
+                compiler: "g++".into(),
+                outcome: Outcome::Passed,
+                elapsed_ms: 12,
+                info: "".into(),
+            },
+            Report {
+                filename: "docs/index.md".into(),
+                line: [8, 12],
+                compiler: "clang++".into(),
+                outcome: Outcome::Failed,
+                elapsed_ms: 7,
+                info: "boom & <bang>".into(),
+            },
+        ]
+    }
+
+    #[test]
+    fn render_junit_counts_failures_and_escapes_info() {
+        let xml = render_junit(&sample_reports(), Some(42));
+        assert!(xml.contains("tests=\"2\" failures=\"1\" skipped=\"0\""));
+        assert!(xml.contains("<property name=\"shuffle.seed\" value=\"42\"/>"));
+        assert!(xml.contains("boom &amp; &lt;bang&gt;"));
+    }
+
+    #[test]
+    fn render_tap_marks_not_ok_and_includes_seed() {
+        let tap = render_tap(&sample_reports(), Some(42));
+        assert!(tap.starts_with("TAP version 13\n1..2\n"));
+        assert!(tap.contains("# shuffle seed: 42"));
+        assert!(tap.contains("ok 1 -"));
+        assert!(tap.contains("not ok 2 -"));
+    }
+
+    #[test]
+    fn xorshift64_is_seeded_and_reproducible() {
+        let mut a = Xorshift64::new(7);
+        let mut b = Xorshift64::new(7);
+        assert_eq!(a.next_u64(), b.next_u64());
+
+        let mut items_a = vec![1, 2, 3, 4, 5];
+        let mut items_b = items_a.clone();
+        Xorshift64::new(1234).shuffle(&mut items_a);
+        Xorshift64::new(1234).shuffle(&mut items_b);
+        assert_eq!(items_a, items_b);
+        assert_ne!(items_a, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn fnv1a_is_deterministic_and_sensitive_to_input() {
+        assert_eq!(fnv1a(b"docs/index.md"), fnv1a(b"docs/index.md"));
+        assert_ne!(fnv1a(b"docs/index.md"), fnv1a(b"docs/other.md"));
+    }
+
+    #[test]
+    fn extract_expect_trailer_present_absent_and_single_line() {
+        let (code, expected) =
+            extract_expect_trailer("int main() {\n    return 0;\n}\n// expect: 0");
+        assert_eq!(code, "int main() {\n    return 0;\n}");
+        assert_eq!(expected.as_deref(), Some("0"));
+
+        let (code, expected) = extract_expect_trailer("int main() { return 0; }");
+        assert_eq!(code, "int main() { return 0; }");
+        assert_eq!(expected, None);
+
+        // A snippet consisting solely of the trailer has no code left over;
+        // the caller ends up compiling an empty file.
+        let (code, expected) = extract_expect_trailer("// expect: x");
+        assert_eq!(code, "");
+        assert_eq!(expected.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn read_the_docs_wires_no_run_and_ignore_attrs() {
+        let dir = TempDir::new("read-the-docs-test").unwrap();
+        let path = dir.path().join("doc.md");
+        std::fs::write(
+            &path,
+            "# Title\n\n```cpp no_run\nint main() {}\n```\n\n```cpp ignore\nbroken(\n```\n",
+        )
+        .unwrap();
+
+        let cases = read_the_docs(&path, "cpp").unwrap();
+
+        assert_eq!(cases.len(), 2);
+        assert!(cases[0].no_run);
+        assert!(!cases[0].ignore);
+        assert!(cases[1].ignore);
+        assert!(!cases[1].no_run);
+    }
+
+    #[test]
+    fn read_the_docs_tracks_headers_and_captures_adjacent_expect() {
+        let dir = TempDir::new("read-the-docs-test").unwrap();
+        let path = dir.path().join("doc.md");
+        std::fs::write(
+            &path,
+            "# Title\n## Sub\n\n```cpp\nint a() {}\n```\n\n```text\nA\n```\n\n# Other\n\n```cpp\nint b() {}\n```\n",
+        )
+        .unwrap();
+
+        let cases = read_the_docs(&path, "cpp").unwrap();
+
+        assert_eq!(cases.len(), 2);
+        // Under "# Title" / "## Sub", with an adjacent ```text block capturing
+        // the expected stdout for the case directly above it.
+        assert_eq!(cases[0].header, format!("{:?}", vec!["Title", "Sub"]));
+        assert_eq!(cases[0].expected_stdout.as_deref(), Some("A"));
+        // A later top-level "# Other" overwrites only header[0]; the deeper
+        // "Sub" slot is left stale since no new "##" has overwritten it.
+        assert_eq!(cases[1].header, format!("{:?}", vec!["Other", "Sub"]));
+        assert_eq!(cases[1].expected_stdout, None);
+    }
+}